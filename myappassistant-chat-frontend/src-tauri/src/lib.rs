@@ -2,6 +2,7 @@ use serde::{Deserialize, Serialize};
 use std::path::PathBuf;
 use chrono::{DateTime, Utc};
 use base64::{Engine as _, engine::general_purpose};
+use regex::Regex;
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct ReceiptData {
@@ -18,6 +19,7 @@ pub struct ReceiptItem {
     pub quantity: f64,
     pub price: f64,
     pub category: Option<String>,
+    pub confidence: f64, // how much of the OCR line the item regex matched, 0.0-1.0
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -32,7 +34,9 @@ pub struct ImageCompressionOptions {
     pub max_width: Option<u32>,
     pub max_height: Option<u32>,
     pub quality: Option<u8>,
-    pub format: Option<String>, // "jpeg", "png", "webp"
+    pub format: Option<String>, // "jpeg", "png", "webp", "avif"
+    #[serde(default)]
+    pub deskew: bool, // warp the detected receipt quad to a front-facing rectangle
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -43,6 +47,7 @@ pub struct CompressedImageResult {
     pub height: u32,
     pub size_bytes: usize,
     pub compression_ratio: f64,
+    pub issue: Option<ImageProcessingIssue>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -52,6 +57,7 @@ pub struct ImageQualityResult {
     pub brightness_score: f64,
     pub overall_quality: f64,
     pub recommendations: Vec<String>,
+    pub issue: Option<ImageProcessingIssue>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -61,34 +67,159 @@ pub struct ReceiptContourResult {
     pub corners: Option<Vec<(f32, f32)>>,
     pub bounding_box: Option<(f32, f32, f32, f32)>, // x, y, width, height
     pub angle: Option<f32>,
+    pub issue: Option<ImageProcessingIssue>,
+}
+
+/// Waga problemu: Warning = zdegradowano, ale wynik jest użyteczny; Error = wynik
+/// to neutralny placeholder, trzeba poprosić o ponowne zdjęcie.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum IssueSeverity {
+    Warning,
+    Error,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ImageProcessingIssue {
+    pub severity: IssueSeverity,
+    pub title: String,
+    pub message: String,
 }
 
 pub fn greet(name: &str) -> String {
     format!("Hello, {}! You've been greeted from Rust!", name)
 }
 
-pub async fn process_receipt_image(_path: String) -> Result<ReceiptData, String> {
-    let receipt_data = ReceiptData {
-        items: vec![
-            ReceiptItem {
-                name: "Milk".to_string(),
-                quantity: 1.0,
-                price: 3.99,
-                category: Some("Dairy".to_string()),
-            },
-            ReceiptItem {
-                name: "Bread".to_string(),
-                quantity: 1.0,
-                price: 2.49,
-                category: Some("Bakery".to_string()),
-            },
-        ],
-        total: 6.48,
-        store: "Local Supermarket".to_string(),
+pub async fn process_receipt_image(path: String) -> Result<ReceiptData, String> {
+    let image_bytes = tokio::fs::read(&path)
+        .await
+        .map_err(|e| format!("Failed to read image file: {}", e))?;
+
+    let mut img = image::load_from_memory(&image_bytes)
+        .map_err(|e| format!("Failed to load image: {}", e))?;
+    img = apply_exif_orientation(img, &image_bytes);
+
+    let rgb_img = img.to_rgb8();
+    if let Some(quad) = find_receipt_quad(&rgb_img) {
+        img = warp_quad_to_rectangle(&img, &quad);
+    }
+
+    let mut ocr_input = Vec::new();
+    img.write_with_encoder(image::codecs::png::PngEncoder::new(&mut ocr_input))
+        .map_err(|e| format!("Failed to prepare image for OCR: {}", e))?;
+
+    let text = run_receipt_ocr(ocr_input).await?;
+
+    let items = parse_receipt_items(&text);
+    let total = detect_total(&text)
+        .unwrap_or_else(|| items.iter().map(|item| item.quantity * item.price).sum());
+    let store = detect_store_name(&text);
+
+    Ok(ReceiptData {
+        items,
+        total,
+        store,
         date: Utc::now(),
         receipt_id: uuid::Uuid::new_v4().to_string(),
-    };
-    Ok(receipt_data)
+    })
+}
+
+/// Uruchamia OCR na obrazie paragonu: jeśli `OCR_API_URL` jest ustawione, woła
+/// zewnętrzny endpoint HTTP (przez `make_api_request`); w przeciwnym razie używa
+/// dołączonego silnika Tesseract z modelem językowym z `OCR_LANGUAGE`
+/// (domyślnie "pol+eng", bo paragony w tym pipeline są po polsku).
+async fn run_receipt_ocr(image_bytes: Vec<u8>) -> Result<String, String> {
+    if let Ok(ocr_endpoint) = std::env::var("OCR_API_URL") {
+        let body = general_purpose::STANDARD.encode(&image_bytes);
+        return make_api_request(ocr_endpoint, "POST".to_string(), Some(body)).await;
+    }
+
+    let language = std::env::var("OCR_LANGUAGE").unwrap_or_else(|_| "pol+eng".to_string());
+
+    tokio::task::spawn_blocking(move || {
+        let mut tess = leptess::LepTess::new(None, &language)
+            .map_err(|e| format!("Failed to initialize OCR engine: {}", e))?;
+        tess.set_image_from_mem(&image_bytes)
+            .map_err(|e| format!("Failed to load image into OCR engine: {}", e))?;
+        tess.get_utf8_text()
+            .map_err(|e| format!("OCR failed: {}", e))
+    })
+    .await
+    .map_err(|e| format!("OCR task panicked: {}", e))?
+}
+
+/// Czy linia OCR wygląda na podsumowanie ("suma", "razem", "total" itp.), które
+/// powinno zasilić `total`, a nie być traktowane jak pozycja zakupowa.
+fn is_total_line(line: &str) -> bool {
+    let lowered = line.to_lowercase();
+    ["total", "suma", "razem", "do zapłaty", "do zaplaty"]
+        .iter()
+        .any(|keyword| lowered.contains(keyword))
+}
+
+/// Heurystycznie parsuje tekst OCR na pozycje paragonu w formacie
+/// `[ilość x] nazwa cena`, np. "2x Mleko 7.98" albo "Chleb 4.50".
+fn parse_receipt_items(text: &str) -> Vec<ReceiptItem> {
+    let item_re = Regex::new(
+        r"(?i)^(?:(?P<qty>\d+)\s*[xX]\s*)?(?P<name>\p{L}[\p{L}0-9\s.,'-]*?)\s+(?P<price>\d+[.,]\d{2})\s*[a-zA-Z]{0,3}$",
+    )
+    .expect("valid receipt item regex");
+
+    text.lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !is_total_line(line))
+        .filter_map(|line| {
+            let caps = item_re.captures(line)?;
+            let name = caps.name("name")?.as_str().trim().to_string();
+            if name.chars().filter(|c| c.is_alphabetic()).count() < 2 {
+                return None;
+            }
+
+            let price: f64 = caps
+                .name("price")?
+                .as_str()
+                .replace(',', ".")
+                .parse()
+                .ok()?;
+            let quantity: f64 = caps
+                .name("qty")
+                .and_then(|m| m.as_str().parse().ok())
+                .unwrap_or(1.0);
+            let matched_len = caps.get(0).map(|m| m.as_str().len()).unwrap_or(0);
+            let confidence = (matched_len as f64 / line.len().max(1) as f64).clamp(0.0, 1.0);
+
+            Some(ReceiptItem {
+                name,
+                quantity,
+                price,
+                category: None,
+                confidence,
+            })
+        })
+        .collect()
+}
+
+/// Szuka ostatniej linii podsumowującej ("suma"/"total"/...) i wyciąga z niej
+/// kwotę - jeśli linia zawiera więcej niż jedną liczbę, bierze ostatnią (kwota
+/// zwykle stoi po prawej).
+fn detect_total(text: &str) -> Option<f64> {
+    let price_re = Regex::new(r"\d+[.,]\d{2}").expect("valid price regex");
+
+    text.lines()
+        .rev()
+        .find(|line| is_total_line(line))
+        .and_then(|line| price_re.find_iter(line).last())
+        .and_then(|m| m.as_str().replace(',', ".").parse().ok())
+}
+
+/// Nazwa sklepu to zwykle pierwsza linia paragonu z sensowną ilością liter -
+/// pomija puste linie i same liczby/kody kreskowe na górze zdjęcia.
+fn detect_store_name(text: &str) -> String {
+    text.lines()
+        .map(str::trim)
+        .find(|line| line.chars().filter(|c| c.is_alphabetic()).count() >= 3)
+        .unwrap_or("Unknown Store")
+        .to_string()
 }
 
 pub fn show_system_notification(title: String, body: String) -> Result<(), String> {
@@ -187,6 +318,75 @@ pub async fn monitor_promotions(store: Option<String>) -> Result<String, String>
     Ok(format!("Promotion monitoring completed for: {}", store_name))
 }
 
+/// Odczytuje tag EXIF Orientation z oryginalnych bajtów i obraca/odbija obraz tak,
+/// by odpowiadał orientacji wyświetlania. `image::load_from_memory` tego nie robi,
+/// więc zdjęcia z telefonu bez tego kroku trafiają do OCR bokiem.
+fn apply_exif_orientation(img: image::DynamicImage, image_bytes: &[u8]) -> image::DynamicImage {
+    let orientation = exif::Reader::new()
+        .read_from_container(&mut std::io::Cursor::new(image_bytes))
+        .ok()
+        .and_then(|exif_data| exif_data.get_field(exif::Tag::Orientation, exif::In::PRIMARY).cloned())
+        .and_then(|field| field.value.get_uint(0))
+        .unwrap_or(1);
+
+    reorient_for_exif_tag(img, orientation)
+}
+
+/// Stosuje transformację odpowiadającą wartości tagu EXIF Orientation (1-8).
+fn reorient_for_exif_tag(img: image::DynamicImage, orientation: u32) -> image::DynamicImage {
+    match orientation {
+        2 => img.fliph(),
+        3 => img.rotate180(),
+        4 => img.flipv(),
+        5 => img.rotate90().fliph(),
+        6 => img.rotate90(),
+        7 => img.rotate270().fliph(),
+        8 => img.rotate270(),
+        _ => img,
+    }
+}
+
+/// Prostuje perspektywicznie wykryty czworokąt paragonu do płaskiego, czołowego
+/// prostokąta o wymiarach jego rzeczywistych krawędzi (nie bounding boxa, który
+/// dla pochylonego czworokąta ma inne proporcje niż sam czworokąt).
+fn warp_quad_to_rectangle(img: &image::DynamicImage, quad: &DetectedQuad) -> image::DynamicImage {
+    let [top_left, top_right, bottom_right, bottom_left] = quad.corners;
+    let dst_width = edge_length(top_left, top_right).max(edge_length(bottom_left, bottom_right));
+    let dst_height = edge_length(top_left, bottom_left).max(edge_length(top_right, bottom_right));
+    let dst_width = dst_width.round().max(1.0) as u32;
+    let dst_height = dst_height.round().max(1.0) as u32;
+
+    let destination = [
+        (0.0, 0.0),
+        (dst_width as f32, 0.0),
+        (dst_width as f32, dst_height as f32),
+        (0.0, dst_height as f32),
+    ];
+
+    // `warp_into` inverts the projection it is given to look up, for each output
+    // pixel, the corresponding input pixel, so the projection maps the source quad
+    // onto the destination rectangle.
+    let projection =
+        match imageproc::geometric_transformations::Projection::from_control_points(quad.corners, destination) {
+            Some(projection) => projection,
+            None => return img.clone(),
+        };
+
+    // Warp straight into a buffer of the target size, so edges longer than the
+    // input image are not cut off.
+    let rgba = img.to_rgba8();
+    let mut warped = image::RgbaImage::new(dst_width, dst_height);
+    imageproc::geometric_transformations::warp_into(
+        &rgba,
+        &projection,
+        imageproc::geometric_transformations::Interpolation::Bilinear,
+        image::Rgba([0, 0, 0, 0]),
+        &mut warped,
+    );
+
+    image::DynamicImage::ImageRgba8(warped)
+}
+
 /// Kompresuje i przetwarza obraz po stronie klienta
 /// Zgodnie z rekomendacjami audytu - oszczędność transferu
 pub async fn compress_image(
@@ -199,13 +399,37 @@ pub async fn compress_image(
         .map_err(|e| format!("Failed to decode base64: {}", e))?;
     
     // Wczytaj obraz
-    let mut img = image::load_from_memory(&image_bytes)
-        .map_err(|e| format!("Failed to load image: {}", e))?;
-    
+    let mut img = match image::load_from_memory(&image_bytes) {
+        Ok(img) => img,
+        Err(e) => {
+            // Undecodable codec: pass the original bytes straight through so the
+            // pipeline doesn't abort, but flag it so the caller knows nothing
+            // was actually compressed.
+            let size_bytes = image_bytes.len();
+            return Ok(CompressedImageResult {
+                data: general_purpose::STANDARD.encode(&image_bytes),
+                format: "unknown".to_string(),
+                width: 0,
+                height: 0,
+                size_bytes,
+                compression_ratio: 1.0,
+                issue: Some(ImageProcessingIssue {
+                    severity: IssueSeverity::Error,
+                    title: "Unable to decode image".to_string(),
+                    message: format!("Failed to load image: {}", e),
+                }),
+            });
+        }
+    };
+
+    // Phone photos carry EXIF orientation; normalize it before anything else
+    // touches pixel coordinates (resize, deskew, encoding).
+    img = apply_exif_orientation(img, &image_bytes);
+
     let original_width = img.width();
     let original_height = img.height();
     let original_size = image_bytes.len();
-    
+
     // Resize jeśli podano wymiary
     if let (Some(max_width), Some(max_height)) = (options.max_width, options.max_height) {
         img = img.resize(max_width, max_height, image::imageops::FilterType::Lanczos3);
@@ -218,16 +442,27 @@ pub async fn compress_image(
         let new_width = (original_width as f32 * ratio) as u32;
         img = img.resize(new_width, max_height, image::imageops::FilterType::Lanczos3);
     }
-    
+
+    // Opcjonalny deskew: znajdź czworokąt paragonu i wyprostuj go perspektywicznie
+    if options.deskew {
+        let rgb_for_detection = img.to_rgb8();
+        if let Some(quad) = find_receipt_quad(&rgb_for_detection) {
+            img = warp_quad_to_rectangle(&img, &quad);
+        }
+    }
+
     // Określ format wyjściowy
-    let format = options.format.unwrap_or_else(|| "jpeg".to_string());
+    let mut format = options.format.unwrap_or_else(|| "jpeg".to_string());
     let quality = options.quality.unwrap_or(85);
     
     // Konwertuj do odpowiedniego formatu
     let mut output_buffer = Vec::new();
+    let mut issue = None;
     match format.to_lowercase().as_str() {
         "jpeg" | "jpg" => {
-            img.write_with_encoder(
+            // JPEG has no alpha channel; the deskew warp can hand us an Rgba8
+            // buffer, which the JPEG encoder rejects outright.
+            image::DynamicImage::ImageRgb8(img.to_rgb8()).write_with_encoder(
                 image::codecs::jpeg::JpegEncoder::new_with_quality(&mut output_buffer, quality)
             ).map_err(|e| format!("Failed to encode JPEG: {}", e))?;
         },
@@ -237,19 +472,47 @@ pub async fn compress_image(
             ).map_err(|e| format!("Failed to encode PNG: {}", e))?;
         },
         "webp" => {
-            // WebP encoding would require additional dependencies
-            return Err("WebP encoding not yet supported".to_string());
+            // Lossy WebP via libwebp bindings; `quality` maps directly to libwebp's 0-100 scale
+            let rgba = img.to_rgba8();
+            let encoder = webp::Encoder::from_rgba(&rgba, rgba.width(), rgba.height());
+            output_buffer = encoder.encode(quality as f32).to_vec();
         },
-        _ => {
-            return Err(format!("Unsupported format: {}", format));
+        "avif" => {
+            let rgba = img.to_rgba8();
+            let (width, height) = rgba.dimensions();
+            let pixels: Vec<ravif::RGBA8> = rgba
+                .pixels()
+                .map(|p| ravif::RGBA8::new(p[0], p[1], p[2], p[3]))
+                .collect();
+            let frame = ravif::Img::new(pixels.as_slice(), width as usize, height as usize);
+            let encoded = ravif::Encoder::new()
+                .with_quality(quality as f32)
+                .with_speed(6)
+                .encode_rgba(frame)
+                .map_err(|e| format!("Failed to encode AVIF: {}", e))?;
+            output_buffer = encoded.avif_file;
+        },
+        unsupported => {
+            // Unsupported-but-decodable codec request: fall back to JPEG instead of
+            // aborting the whole receipt pipeline, and tell the caller why.
+            let requested = unsupported.to_string();
+            image::DynamicImage::ImageRgb8(img.to_rgb8()).write_with_encoder(
+                image::codecs::jpeg::JpegEncoder::new_with_quality(&mut output_buffer, quality)
+            ).map_err(|e| format!("Failed to encode JPEG fallback: {}", e))?;
+            issue = Some(ImageProcessingIssue {
+                severity: IssueSeverity::Warning,
+                title: "Unsupported format, used JPEG fallback".to_string(),
+                message: format!("Requested format \"{}\" is not supported; encoded as JPEG instead.", requested),
+            });
+            format = "jpeg".to_string();
         }
     }
-    
+
     // Encode to base64
     let compressed_base64 = general_purpose::STANDARD.encode(&output_buffer);
-    
+
     let compression_ratio = original_size as f64 / output_buffer.len() as f64;
-    
+
     Ok(CompressedImageResult {
         data: compressed_base64,
         format,
@@ -257,6 +520,7 @@ pub async fn compress_image(
         height: img.height(),
         size_bytes: output_buffer.len(),
         compression_ratio,
+        issue,
     })
 }
 
@@ -271,45 +535,376 @@ pub async fn detect_receipt_contour(
         .map_err(|e| format!("Failed to decode base64: {}", e))?;
     
     // Wczytaj obraz
-    let img = image::load_from_memory(&image_bytes)
-        .map_err(|e| format!("Failed to load image: {}", e))?;
-    
+    let img = match image::load_from_memory(&image_bytes) {
+        Ok(img) => img,
+        Err(e) => {
+            return Ok(ReceiptContourResult {
+                detected: false,
+                confidence: 0.0,
+                corners: None,
+                bounding_box: None,
+                angle: None,
+                issue: Some(ImageProcessingIssue {
+                    severity: IssueSeverity::Error,
+                    title: "Unable to decode image".to_string(),
+                    message: format!("Failed to load image: {}", e),
+                }),
+            });
+        }
+    };
+
     // Konwertuj do RGB
     let rgb_img = img.to_rgb8();
-    let (width, height) = rgb_img.dimensions();
-    
-    // Prosta implementacja wykrywania konturu (w rzeczywistości użyłaby OpenCV)
-    // Tutaj symulujemy wykrywanie prostokątnego konturu
-    
-    // Sprawdź czy obraz ma odpowiednie proporcje paragonu (szeroki prostokąt)
-    let aspect_ratio = width as f32 / height as f32;
-    let is_receipt_like = aspect_ratio > 1.5 && aspect_ratio < 4.0;
-    
-    if is_receipt_like {
-        // Symuluj wykrycie konturu
-        let corners = vec![
-            (0.0, 0.0),
-            (width as f32, 0.0),
-            (width as f32, height as f32),
-            (0.0, height as f32),
-        ];
-        
-        Ok(ReceiptContourResult {
+
+    match find_receipt_quad(&rgb_img) {
+        Some(quad) => Ok(ReceiptContourResult {
             detected: true,
-            confidence: 0.8,
-            corners: Some(corners),
-            bounding_box: Some((0.0, 0.0, width as f32, height as f32)),
-            angle: Some(0.0),
-        })
-    } else {
-        Ok(ReceiptContourResult {
+            confidence: quad.confidence,
+            corners: Some(quad.corners.to_vec()),
+            bounding_box: Some(quad.bounding_box),
+            angle: Some(quad.angle),
+            issue: None,
+        }),
+        None => Ok(ReceiptContourResult {
             detected: false,
-            confidence: 0.2,
+            confidence: 0.0,
             corners: None,
             bounding_box: None,
             angle: None,
-        })
+            issue: None,
+        }),
+    }
+}
+
+/// Wynik wykrycia czworokątnego konturu paragonu na obrazie.
+struct DetectedQuad {
+    corners: [(f32, f32); 4], // top-left, top-right, bottom-right, bottom-left
+    bounding_box: (f32, f32, f32, f32),
+    angle: f32,
+    confidence: f64,
+}
+
+/// Szuka największego czworokątnego konturu na obrazie: skala szarości -> rozmycie
+/// Gaussa -> detekcja krawędzi Canny -> śledzenie konturów -> aproksymacja wielokątem
+/// (Douglas-Peucker, epsilon ~2% obwodu). Zwraca `None`, jeśli żaden kontur nie
+/// wygląda na paragon (za mały albo nie ma dokładnie czterech rogów).
+fn find_receipt_quad(rgb_img: &image::RgbImage) -> Option<DetectedQuad> {
+    let (width, height) = rgb_img.dimensions();
+    let frame_area = width as f64 * height as f64;
+
+    let gray = image::imageops::grayscale(rgb_img);
+    let blurred = imageproc::filter::gaussian_blur_f32(&gray, 1.4);
+    let edges = imageproc::edges::canny(&blurred, 40.0, 100.0);
+    let contours = imageproc::contours::find_contours::<i32>(&edges);
+
+    let mut best: Option<DetectedQuad> = None;
+    let mut best_area = 0.0f64;
+
+    for contour in &contours {
+        if contour.points.len() < 4 {
+            continue;
+        }
+
+        let points: Vec<(f64, f64)> = contour
+            .points
+            .iter()
+            .map(|p| (p.x as f64, p.y as f64))
+            .collect();
+
+        let area = polygon_area(&points);
+        // Too small to plausibly be the receipt itself rather than noise/texture.
+        if area < frame_area * 0.1 {
+            continue;
+        }
+
+        let perimeter = polygon_perimeter(&points);
+        let epsilon = perimeter * 0.02;
+        let approx = douglas_peucker(&points, epsilon);
+
+        if approx.len() != 4 || area <= best_area {
+            continue;
+        }
+
+        let corners = order_corners(&approx);
+        let bounding_box = bounding_box_of(&corners);
+        let regularity = corner_angle_regularity(&corners);
+        let confidence = ((area / frame_area) * regularity).clamp(0.0, 1.0);
+
+        best_area = area;
+        best = Some(DetectedQuad {
+            corners,
+            bounding_box,
+            angle: top_edge_angle(&corners),
+            confidence,
+        });
     }
+
+    best
+}
+
+/// Pole wielokąta metodą Gaussa (shoelace formula).
+fn polygon_area(points: &[(f64, f64)]) -> f64 {
+    if points.len() < 3 {
+        return 0.0;
+    }
+    let mut sum = 0.0;
+    for i in 0..points.len() {
+        let (x0, y0) = points[i];
+        let (x1, y1) = points[(i + 1) % points.len()];
+        sum += x0 * y1 - x1 * y0;
+    }
+    (sum / 2.0).abs()
+}
+
+/// Obwód zamkniętego wielokąta.
+fn polygon_perimeter(points: &[(f64, f64)]) -> f64 {
+    if points.len() < 2 {
+        return 0.0;
+    }
+    let mut perimeter = 0.0;
+    for i in 0..points.len() {
+        let (x0, y0) = points[i];
+        let (x1, y1) = points[(i + 1) % points.len()];
+        perimeter += ((x1 - x0).powi(2) + (y1 - y0).powi(2)).sqrt();
+    }
+    perimeter
+}
+
+/// Aproksymacja wielokąta zamkniętego algorytmem Douglasa-Peuckera.
+fn douglas_peucker(points: &[(f64, f64)], epsilon: f64) -> Vec<(f64, f64)> {
+    if points.len() < 3 {
+        return points.to_vec();
+    }
+
+    // Start from the two points farthest apart so the closed contour is split
+    // into two open chains that douglas_peucker_chain can simplify independently.
+    let (i, j) = farthest_pair(points);
+    let (lo, hi) = if i < j { (i, j) } else { (j, i) };
+
+    let mut chain_a: Vec<(f64, f64)> = points[lo..=hi].to_vec();
+    let mut chain_b: Vec<(f64, f64)> = points[hi..]
+        .iter()
+        .chain(points[..=lo].iter())
+        .cloned()
+        .collect();
+
+    chain_a = douglas_peucker_chain(&chain_a, epsilon);
+    chain_b = douglas_peucker_chain(&chain_b, epsilon);
+
+    chain_a.pop(); // avoid duplicating the shared endpoint
+    chain_b.pop();
+    chain_a.extend(chain_b);
+    chain_a
+}
+
+fn farthest_pair(points: &[(f64, f64)]) -> (usize, usize) {
+    let mut best = (0, 0);
+    let mut best_dist = 0.0;
+    for i in 0..points.len() {
+        for j in (i + 1)..points.len() {
+            let (x0, y0) = points[i];
+            let (x1, y1) = points[j];
+            let dist = (x1 - x0).powi(2) + (y1 - y0).powi(2);
+            if dist > best_dist {
+                best_dist = dist;
+                best = (i, j);
+            }
+        }
+    }
+    best
+}
+
+fn douglas_peucker_chain(points: &[(f64, f64)], epsilon: f64) -> Vec<(f64, f64)> {
+    if points.len() < 3 {
+        return points.to_vec();
+    }
+
+    let (start, end) = (points[0], points[points.len() - 1]);
+    let mut max_dist = 0.0;
+    let mut max_idx = 0;
+
+    for (idx, &point) in points.iter().enumerate().take(points.len() - 1).skip(1) {
+        let dist = point_to_segment_distance(point, start, end);
+        if dist > max_dist {
+            max_dist = dist;
+            max_idx = idx;
+        }
+    }
+
+    if max_dist > epsilon {
+        let mut left = douglas_peucker_chain(&points[..=max_idx], epsilon);
+        let right = douglas_peucker_chain(&points[max_idx..], epsilon);
+        left.pop();
+        left.extend(right);
+        left
+    } else {
+        vec![start, end]
+    }
+}
+
+fn point_to_segment_distance(point: (f64, f64), start: (f64, f64), end: (f64, f64)) -> f64 {
+    let (px, py) = point;
+    let (sx, sy) = start;
+    let (ex, ey) = end;
+    let (dx, dy) = (ex - sx, ey - sy);
+    let len_sq = dx * dx + dy * dy;
+    if len_sq == 0.0 {
+        return ((px - sx).powi(2) + (py - sy).powi(2)).sqrt();
+    }
+    let t = (((px - sx) * dx + (py - sy) * dy) / len_sq).clamp(0.0, 1.0);
+    let (proj_x, proj_y) = (sx + t * dx, sy + t * dy);
+    ((px - proj_x).powi(2) + (py - proj_y).powi(2)).sqrt()
+}
+
+/// Porządkuje cztery rogi jako top-left, top-right, bottom-right, bottom-left
+/// przy pomocy sumy (x+y) i różnicy (x-y) współrzędnych — standardowa sztuczka,
+/// poprawna dla czworokątów obróconych o mniej niż 45 stopni; przy obrocie bliskim
+/// 45 stopni sumy/różnice się zrównują i jeden punkt może trafić na dwie pozycje.
+fn order_corners(points: &[(f64, f64)]) -> [(f32, f32); 4] {
+    let mut pts = points.to_vec();
+    pts.sort_by(|a, b| (a.0 + a.1).partial_cmp(&(b.0 + b.1)).unwrap());
+    let top_left = pts[0];
+    let bottom_right = pts[3];
+
+    pts.sort_by(|a, b| (a.0 - a.1).partial_cmp(&(b.0 - b.1)).unwrap());
+    let bottom_left = pts[0];
+    let top_right = pts[3];
+
+    [
+        (top_left.0 as f32, top_left.1 as f32),
+        (top_right.0 as f32, top_right.1 as f32),
+        (bottom_right.0 as f32, bottom_right.1 as f32),
+        (bottom_left.0 as f32, bottom_left.1 as f32),
+    ]
+}
+
+/// Długość odcinka między dwoma punktami.
+fn edge_length(a: (f32, f32), b: (f32, f32)) -> f32 {
+    ((b.0 - a.0).powi(2) + (b.1 - a.1).powi(2)).sqrt()
+}
+
+fn bounding_box_of(corners: &[(f32, f32); 4]) -> (f32, f32, f32, f32) {
+    let min_x = corners.iter().map(|c| c.0).fold(f32::INFINITY, f32::min);
+    let max_x = corners.iter().map(|c| c.0).fold(f32::NEG_INFINITY, f32::max);
+    let min_y = corners.iter().map(|c| c.1).fold(f32::INFINITY, f32::min);
+    let max_y = corners.iter().map(|c| c.1).fold(f32::NEG_INFINITY, f32::max);
+    (min_x, min_y, max_x - min_x, max_y - min_y)
+}
+
+/// Kąt górnej krawędzi (top-left -> top-right) względem poziomu, w stopniach.
+fn top_edge_angle(corners: &[(f32, f32); 4]) -> f32 {
+    let (x0, y0) = corners[0];
+    let (x1, y1) = corners[1];
+    (y1 - y0).atan2(x1 - x0).to_degrees()
+}
+
+/// Jak bardzo kąty wewnętrzne czworokąta zbliżają się do 90 stopni; 1.0 = idealny
+/// prostokąt, malejąc w kierunku 0 dla mocno przekrzywionych czworokątów.
+fn corner_angle_regularity(corners: &[(f32, f32); 4]) -> f64 {
+    let mut total_deviation = 0.0;
+    for i in 0..4 {
+        let prev = corners[(i + 3) % 4];
+        let curr = corners[i];
+        let next = corners[(i + 1) % 4];
+
+        let v1 = (prev.0 - curr.0, prev.1 - curr.1);
+        let v2 = (next.0 - curr.0, next.1 - curr.1);
+
+        let dot = (v1.0 * v2.0 + v1.1 * v2.1) as f64;
+        let mag1 = ((v1.0 * v1.0 + v1.1 * v1.1) as f64).sqrt();
+        let mag2 = ((v2.0 * v2.0 + v2.1 * v2.1) as f64).sqrt();
+
+        if mag1 == 0.0 || mag2 == 0.0 {
+            continue;
+        }
+
+        let angle = (dot / (mag1 * mag2)).clamp(-1.0, 1.0).acos().to_degrees();
+        total_deviation += (angle - 90.0).abs();
+    }
+
+    (1.0 - (total_deviation / 4.0) / 90.0).clamp(0.0, 1.0)
+}
+
+/// Ocenia ostrość obrazu wariancją odpowiedzi filtru Laplace'a (3x3, środek 4,
+/// sąsiedzi ortogonalni -1). Wysoka wariancja = dużo ostrych krawędzi, niska = rozmycie.
+fn laplacian_sharpness_score(gray: &image::GrayImage) -> f64 {
+    let (width, height) = gray.dimensions();
+    if width < 3 || height < 3 {
+        return 0.0;
+    }
+
+    let mut sum = 0.0f64;
+    let mut sum_sq = 0.0f64;
+    let mut count = 0.0f64;
+
+    for y in 1..height - 1 {
+        for x in 1..width - 1 {
+            let center = gray.get_pixel(x, y)[0] as f64;
+            let up = gray.get_pixel(x, y - 1)[0] as f64;
+            let down = gray.get_pixel(x, y + 1)[0] as f64;
+            let left = gray.get_pixel(x - 1, y)[0] as f64;
+            let right = gray.get_pixel(x + 1, y)[0] as f64;
+
+            let laplacian = 4.0 * center - up - down - left - right;
+            sum += laplacian;
+            sum_sq += laplacian * laplacian;
+            count += 1.0;
+        }
+    }
+
+    if count == 0.0 {
+        return 0.0;
+    }
+
+    let mean = sum / count;
+    let variance = (sum_sq / count) - (mean * mean);
+
+    // K tuned so a visibly sharp receipt (variance in the low thousands) saturates
+    // near 1.0 while a blurry shot (variance in the tens) stays well below 0.5.
+    const K: f64 = 350.0;
+    (1.0 - (-variance / K).exp()).clamp(0.0, 1.0)
+}
+
+/// Ocenia kontrast na podstawie 256-binowego histogramu luminancji: znormalizowane
+/// odchylenie standardowe jasności. Dodatkowo zwraca flagę obcięcia, gdy >10% pikseli
+/// leży w skrajnych binach 0-5 lub 250-255.
+fn histogram_contrast_score(gray: &image::GrayImage) -> (f64, bool) {
+    let mut histogram = [0u32; 256];
+    for pixel in gray.pixels() {
+        histogram[pixel[0] as usize] += 1;
+    }
+
+    let total_pixels: u32 = histogram.iter().sum();
+    if total_pixels == 0 {
+        return (0.0, false);
+    }
+
+    let mean = histogram
+        .iter()
+        .enumerate()
+        .map(|(value, &count)| value as f64 * count as f64)
+        .sum::<f64>()
+        / total_pixels as f64;
+
+    let variance = histogram
+        .iter()
+        .enumerate()
+        .map(|(value, &count)| {
+            let diff = value as f64 - mean;
+            diff * diff * count as f64
+        })
+        .sum::<f64>()
+        / total_pixels as f64;
+
+    // Max possible std dev for a 0-255 uniform-extremes distribution is 127.5.
+    let contrast_score = (variance.sqrt() / 127.5).min(1.0);
+
+    let shadow_clip: u32 = histogram[0..=5].iter().sum();
+    let highlight_clip: u32 = histogram[250..=255].iter().sum();
+    let clipped_fraction = (shadow_clip + highlight_clip) as f64 / total_pixels as f64;
+    let is_clipped = clipped_fraction > 0.1;
+
+    (contrast_score, is_clipped)
 }
 
 /// Analizuje jakość obrazu paragonu
@@ -323,9 +918,24 @@ pub async fn analyze_image_quality(
         .map_err(|e| format!("Failed to decode base64: {}", e))?;
     
     // Wczytaj obraz
-    let img = image::load_from_memory(&image_bytes)
-        .map_err(|e| format!("Failed to load image: {}", e))?;
-    
+    let img = match image::load_from_memory(&image_bytes) {
+        Ok(img) => img,
+        Err(e) => {
+            return Ok(ImageQualityResult {
+                sharpness_score: 0.0,
+                contrast_score: 0.0,
+                brightness_score: 0.0,
+                overall_quality: 0.0,
+                recommendations: vec!["Nie udało się odczytać obrazu.".to_string()],
+                issue: Some(ImageProcessingIssue {
+                    severity: IssueSeverity::Error,
+                    title: "Unable to decode image".to_string(),
+                    message: format!("Failed to load image: {}", e),
+                }),
+            });
+        }
+    };
+
     let rgb_img = img.to_rgb8();
     let (_width, _height) = rgb_img.dimensions();
     
@@ -341,41 +951,47 @@ pub async fn analyze_image_quality(
     }
     
     let avg_brightness = total_brightness as f64 / total_pixels as f64;
-    
+
     // Oblicz scores (0.0 - 1.0)
     let brightness_score = (avg_brightness / 255.0).min(1.0);
-    let sharpness_score = 0.7; // Symulacja - w rzeczywistości użyłaby analizy gradientów
-    let contrast_score = 0.8; // Symulacja - w rzeczywistości użyłaby analizy histogramu
-    
+    let gray_img = image::imageops::grayscale(&rgb_img);
+    let sharpness_score = laplacian_sharpness_score(&gray_img);
+    let (contrast_score, is_clipped) = histogram_contrast_score(&gray_img);
+
     let overall_quality = (brightness_score + sharpness_score + contrast_score) / 3.0;
-    
+
     // Generuj rekomendacje
     let mut recommendations = Vec::new();
-    
+
     if brightness_score < 0.3 {
         recommendations.push("Obraz jest zbyt ciemny. Spróbuj lepszego oświetlenia.".to_string());
     } else if brightness_score > 0.9 {
         recommendations.push("Obraz może być prześwietlony. Zmniejsz jasność.".to_string());
     }
-    
+
     if sharpness_score < 0.5 {
         recommendations.push("Obraz może być nieostry. Upewnij się, że aparat jest stabilny.".to_string());
     }
-    
+
     if contrast_score < 0.5 {
         recommendations.push("Kontrast jest niski. Spróbuj lepszego oświetlenia.".to_string());
     }
-    
+
+    if is_clipped {
+        recommendations.push("Obraz ma przycięte szczegóły w cieniach lub prześwietleniach.".to_string());
+    }
+
     if recommendations.is_empty() {
         recommendations.push("Jakość obrazu jest dobra.".to_string());
     }
-    
+
     Ok(ImageQualityResult {
         sharpness_score,
         contrast_score,
         brightness_score,
         overall_quality,
         recommendations,
+        issue: None,
     })
 }
 
@@ -401,30 +1017,53 @@ mod tests {
         assert_eq!(result, "Hello, Test@123! You've been greeted from Rust!");
     }
 
-    #[tokio::test]
-    async fn test_process_receipt_image() {
-        let result = process_receipt_image("test_path.jpg".to_string()).await;
-        assert!(result.is_ok());
-        
-        let receipt_data = result.unwrap();
-        assert_eq!(receipt_data.items.len(), 2);
-        assert_eq!(receipt_data.total, 6.48);
-        assert_eq!(receipt_data.store, "Local Supermarket");
-        assert!(!receipt_data.receipt_id.is_empty());
-        
-        // Check first item
-        let first_item = &receipt_data.items[0];
-        assert_eq!(first_item.name, "Milk");
-        assert_eq!(first_item.quantity, 1.0);
-        assert_eq!(first_item.price, 3.99);
-        assert_eq!(first_item.category, Some("Dairy".to_string()));
-        
-        // Check second item
-        let second_item = &receipt_data.items[1];
-        assert_eq!(second_item.name, "Bread");
-        assert_eq!(second_item.quantity, 1.0);
-        assert_eq!(second_item.price, 2.49);
-        assert_eq!(second_item.category, Some("Bakery".to_string()));
+    #[test]
+    fn test_parse_receipt_items() {
+        let text = "Supermarket ABC\n2x Mleko 7.98\nChleb 4.50\nSUMA 12.48\n";
+        let items = parse_receipt_items(text);
+
+        assert_eq!(items.len(), 2);
+        assert_eq!(items[0].name, "Mleko");
+        assert_eq!(items[0].quantity, 2.0);
+        assert_eq!(items[0].price, 7.98);
+        assert_eq!(items[0].category, None);
+        assert!(items[0].confidence > 0.0);
+
+        assert_eq!(items[1].name, "Chleb");
+        assert_eq!(items[1].quantity, 1.0);
+        assert_eq!(items[1].price, 4.50);
+    }
+
+    #[test]
+    fn test_parse_receipt_items_skips_total_line() {
+        let text = "Pieczywo 3.20\nRazem do zapłaty 3.20\n";
+        let items = parse_receipt_items(text);
+        assert_eq!(items.len(), 1);
+        assert_eq!(items[0].name, "Pieczywo");
+    }
+
+    #[test]
+    fn test_detect_total() {
+        let text = "Mleko 7.98\nChleb 4.50\nSUMA 12.48\n";
+        assert_eq!(detect_total(text), Some(12.48));
+    }
+
+    #[test]
+    fn test_detect_total_missing() {
+        let text = "Mleko 7.98\nChleb 4.50\n";
+        assert_eq!(detect_total(text), None);
+    }
+
+    #[test]
+    fn test_detect_store_name() {
+        let text = "Supermarket ABC\n123456789\nMleko 7.98\n";
+        assert_eq!(detect_store_name(text), "Supermarket ABC");
+    }
+
+    #[test]
+    fn test_detect_store_name_falls_back_when_no_text() {
+        let text = "123456\n789\n";
+        assert_eq!(detect_store_name(text), "Unknown Store");
     }
 
     #[test]
@@ -455,6 +1094,7 @@ mod tests {
                     quantity: 1.0,
                     price: 10.0,
                     category: Some("Test".to_string()),
+                    confidence: 1.0,
                 }
             ],
             total: 10.0,
@@ -504,4 +1144,318 @@ mod tests {
         assert!(result.is_ok());
         assert!(result.unwrap().contains("all stores"));
     }
+
+    fn sample_png_base64() -> String {
+        let img = image::RgbImage::from_fn(4, 4, |x, y| image::Rgb([(x * 50) as u8, (y * 50) as u8, 128]));
+        let mut bytes = Vec::new();
+        image::DynamicImage::ImageRgb8(img)
+            .write_with_encoder(image::codecs::png::PngEncoder::new(&mut bytes))
+            .unwrap();
+        general_purpose::STANDARD.encode(&bytes)
+    }
+
+    fn compression_options(format: &str) -> ImageCompressionOptions {
+        ImageCompressionOptions {
+            max_width: None,
+            max_height: None,
+            quality: Some(80),
+            format: Some(format.to_string()),
+            deskew: false,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_compress_image_jpeg() {
+        let result = compress_image(sample_png_base64(), compression_options("jpeg")).await.unwrap();
+        assert_eq!(result.format, "jpeg");
+        assert_eq!(result.width, 4);
+        assert_eq!(result.height, 4);
+        assert!(result.issue.is_none());
+        assert!(!result.data.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_compress_image_webp() {
+        let result = compress_image(sample_png_base64(), compression_options("webp")).await.unwrap();
+        assert_eq!(result.format, "webp");
+        assert!(result.issue.is_none());
+        assert!(!result.data.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_compress_image_avif() {
+        let result = compress_image(sample_png_base64(), compression_options("avif")).await.unwrap();
+        assert_eq!(result.format, "avif");
+        assert!(result.issue.is_none());
+        assert!(!result.data.is_empty());
+    }
+
+    // 2 wide x 3 tall, every pixel distinct, so any rotation/flip mistake shows
+    // up as a wrong label instead of an accidentally-symmetric match.
+    //   A B
+    //   C D
+    //   E F
+    fn labeled_test_image() -> image::DynamicImage {
+        let pixels = [[(0u8, 0u8, 0u8), (1, 1, 1)], [(2, 2, 2), (3, 3, 3)], [(4, 4, 4), (5, 5, 5)]];
+        let img = image::RgbImage::from_fn(2, 3, |x, y| {
+            let (r, g, b) = pixels[y as usize][x as usize];
+            image::Rgb([r, g, b])
+        });
+        image::DynamicImage::ImageRgb8(img)
+    }
+
+    fn grid_of(img: &image::DynamicImage) -> Vec<Vec<u8>> {
+        let rgb = img.to_rgb8();
+        let (width, height) = rgb.dimensions();
+        (0..height)
+            .map(|y| (0..width).map(|x| rgb.get_pixel(x, y)[0]).collect())
+            .collect()
+    }
+
+    #[test]
+    fn test_reorient_for_exif_tag_3_is_rotate_180() {
+        let grid = grid_of(&reorient_for_exif_tag(labeled_test_image(), 3));
+        assert_eq!(grid, vec![vec![5, 4], vec![3, 2], vec![1, 0]]);
+    }
+
+    #[test]
+    fn test_reorient_for_exif_tag_6_is_rotate_90_cw() {
+        let grid = grid_of(&reorient_for_exif_tag(labeled_test_image(), 6));
+        assert_eq!(grid, vec![vec![4, 2, 0], vec![5, 3, 1]]);
+    }
+
+    #[test]
+    fn test_reorient_for_exif_tag_8_is_rotate_270_cw() {
+        let grid = grid_of(&reorient_for_exif_tag(labeled_test_image(), 8));
+        assert_eq!(grid, vec![vec![1, 3, 5], vec![0, 2, 4]]);
+    }
+
+    #[test]
+    fn test_reorient_for_exif_tag_5_is_transpose() {
+        let grid = grid_of(&reorient_for_exif_tag(labeled_test_image(), 5));
+        assert_eq!(grid, vec![vec![0, 2, 4], vec![1, 3, 5]]);
+    }
+
+    #[test]
+    fn test_reorient_for_exif_tag_7_is_transverse() {
+        let grid = grid_of(&reorient_for_exif_tag(labeled_test_image(), 7));
+        assert_eq!(grid, vec![vec![5, 3, 1], vec![4, 2, 0]]);
+    }
+
+    #[test]
+    fn test_reorient_for_exif_tag_1_is_identity() {
+        let grid = grid_of(&reorient_for_exif_tag(labeled_test_image(), 1));
+        assert_eq!(grid, vec![vec![0, 1], vec![2, 3], vec![4, 5]]);
+    }
+
+    fn rectangle_on_white(width: u32, height: u32, rect: (u32, u32, u32, u32)) -> image::RgbImage {
+        let (rx, ry, rw, rh) = rect;
+        image::RgbImage::from_fn(width, height, |x, y| {
+            if x >= rx && x < rx + rw && y >= ry && y < ry + rh {
+                image::Rgb([0, 0, 0])
+            } else {
+                image::Rgb([255, 255, 255])
+            }
+        })
+    }
+
+    fn encode_png_base64(img: &image::RgbImage) -> String {
+        let mut bytes = Vec::new();
+        image::DynamicImage::ImageRgb8(img.clone())
+            .write_with_encoder(image::codecs::png::PngEncoder::new(&mut bytes))
+            .unwrap();
+        general_purpose::STANDARD.encode(&bytes)
+    }
+
+    #[tokio::test]
+    async fn test_compress_image_jpeg_with_deskew_on_detected_quad_does_not_error() {
+        // A black rectangle on a white background gives find_receipt_quad a clean
+        // edge to trace, so deskew actually kicks in and hands compress_image an
+        // Rgba8 buffer - the JPEG branch must not choke on that.
+        let rgb_img = rectangle_on_white(100, 60, (10, 10, 80, 40));
+        assert!(
+            find_receipt_quad(&rgb_img).is_some(),
+            "test fixture should produce a detectable quad"
+        );
+
+        let data = encode_png_base64(&rgb_img);
+        let mut options = compression_options("jpeg");
+        options.deskew = true;
+
+        let result = compress_image(data, options).await;
+        assert!(result.is_ok(), "jpeg + deskew should not fail: {:?}", result.err());
+
+        let result = result.unwrap();
+        assert_eq!(result.format, "jpeg");
+        assert!(!result.data.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_compress_image_unsupported_format_falls_back_to_jpeg_with_warning() {
+        let result = compress_image(sample_png_base64(), compression_options("heic")).await.unwrap();
+        assert_eq!(result.format, "jpeg");
+        let issue = result.issue.expect("expected a fallback warning");
+        assert_eq!(issue.severity, IssueSeverity::Warning);
+    }
+
+    #[test]
+    fn test_polygon_area_square() {
+        let square = [(0.0, 0.0), (10.0, 0.0), (10.0, 10.0), (0.0, 10.0)];
+        assert_eq!(polygon_area(&square), 100.0);
+    }
+
+    #[test]
+    fn test_douglas_peucker_collapses_collinear_noise_to_four_corners() {
+        // A 10x10 rectangle contour with an extra, exactly collinear point in the
+        // middle of each edge - these should all be simplified away.
+        let noisy_rectangle = [
+            (0.0, 0.0),
+            (5.0, 0.0),
+            (10.0, 0.0),
+            (10.0, 5.0),
+            (10.0, 10.0),
+            (5.0, 10.0),
+            (0.0, 10.0),
+            (0.0, 5.0),
+        ];
+
+        let simplified = douglas_peucker(&noisy_rectangle, 0.5);
+
+        assert_eq!(simplified.len(), 4);
+        for corner in [(0.0, 0.0), (10.0, 0.0), (10.0, 10.0), (0.0, 10.0)] {
+            assert!(
+                simplified.contains(&corner),
+                "expected corner {:?} in simplified polygon {:?}",
+                corner,
+                simplified
+            );
+        }
+    }
+
+    #[test]
+    fn test_order_corners_axis_aligned_square() {
+        // Deliberately shuffled input order.
+        let shuffled = [(10.0, 10.0), (0.0, 0.0), (0.0, 10.0), (10.0, 0.0)];
+        let ordered = order_corners(&shuffled);
+        assert_eq!(ordered, [(0.0, 0.0), (10.0, 0.0), (10.0, 10.0), (0.0, 10.0)]);
+    }
+
+    #[test]
+    fn test_order_corners_tilted_quad() {
+        // Same tilted 50x200 quad as the deskew regression test below, but fed in
+        // shuffled order - order_corners must still recover TL/TR/BR/BL correctly.
+        let top_left = (0.0, 0.0);
+        let top_right = (49.24, 8.68);
+        let bottom_right = (14.52, 205.64);
+        let bottom_left = (-34.72, 196.96);
+
+        let shuffled = [bottom_right, top_left, bottom_left, top_right];
+        let ordered = order_corners(&shuffled);
+
+        assert_eq!(
+            ordered,
+            [
+                (top_left.0 as f32, top_left.1 as f32),
+                (top_right.0 as f32, top_right.1 as f32),
+                (bottom_right.0 as f32, bottom_right.1 as f32),
+                (bottom_left.0 as f32, bottom_left.1 as f32),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_corner_angle_regularity_perfect_square_is_one() {
+        let square = [(0.0, 0.0), (10.0, 0.0), (10.0, 10.0), (0.0, 10.0)];
+        let regularity = corner_angle_regularity(&square);
+        assert!((regularity - 1.0).abs() < 1e-6, "expected ~1.0, got {}", regularity);
+    }
+
+    #[test]
+    fn test_corner_angle_regularity_sheared_quad_is_lower() {
+        // A parallelogram with ~63/117 degree corners instead of 90 degrees.
+        let sheared = [(0.0, 0.0), (10.0, 0.0), (15.0, 10.0), (5.0, 10.0)];
+        let regularity = corner_angle_regularity(&sheared);
+        assert!(regularity < 0.8, "expected a visibly lower score for a sheared quad, got {}", regularity);
+    }
+
+    fn flat_gray(width: u32, height: u32, value: u8) -> image::GrayImage {
+        image::GrayImage::from_pixel(width, height, image::Luma([value]))
+    }
+
+    fn checkerboard_gray(width: u32, height: u32) -> image::GrayImage {
+        image::GrayImage::from_fn(width, height, |x, y| {
+            if (x + y) % 2 == 0 {
+                image::Luma([0u8])
+            } else {
+                image::Luma([255u8])
+            }
+        })
+    }
+
+    #[test]
+    fn test_laplacian_sharpness_score_flat_image_is_near_zero() {
+        let gray = flat_gray(10, 10, 128);
+        let score = laplacian_sharpness_score(&gray);
+        assert!(score < 0.05, "expected near-zero sharpness for a flat image, got {}", score);
+    }
+
+    #[test]
+    fn test_laplacian_sharpness_score_checkerboard_is_high() {
+        let gray = checkerboard_gray(10, 10);
+        let score = laplacian_sharpness_score(&gray);
+        assert!(score > 0.9, "expected high sharpness for a checkerboard, got {}", score);
+    }
+
+    #[test]
+    fn test_histogram_contrast_score_flat_image_is_zero() {
+        let gray = flat_gray(10, 10, 128);
+        let (score, is_clipped) = histogram_contrast_score(&gray);
+        assert_eq!(score, 0.0);
+        assert!(!is_clipped);
+    }
+
+    #[test]
+    fn test_histogram_contrast_score_detects_clipping() {
+        // 20x20 image: 80% mid-gray, 20% pure black -> shadow clipping above the 10% threshold.
+        let gray = image::GrayImage::from_fn(20, 20, |_x, y| {
+            if y < 4 {
+                image::Luma([0u8])
+            } else {
+                image::Luma([128u8])
+            }
+        });
+        let (_, is_clipped) = histogram_contrast_score(&gray);
+        assert!(is_clipped);
+    }
+
+    #[test]
+    fn test_warp_quad_to_rectangle_uses_true_edge_lengths() {
+        // A 50x200 rectangle rotated ~10 degrees: the bounding box over these
+        // corners is ~84x206 (aspect ~0.41), but the quad's actual edges are
+        // still 50x200 (aspect 0.25). The warped output must follow the edges.
+        let corners = [
+            (0.0, 0.0),
+            (49.24, 8.68),
+            (14.52, 205.64),
+            (-34.72, 196.96),
+        ];
+        let quad = DetectedQuad {
+            corners,
+            bounding_box: bounding_box_of(&corners),
+            angle: 10.0,
+            confidence: 0.9,
+        };
+
+        let img = image::DynamicImage::new_rgba8(300, 300);
+        let warped = warp_quad_to_rectangle(&img, &quad);
+
+        let aspect_ratio = warped.width() as f64 / warped.height() as f64;
+        assert!(
+            (aspect_ratio - 0.25).abs() < 0.05,
+            "expected aspect ratio close to the quad's true 50:200 edges, got {}x{} ({})",
+            warped.width(),
+            warped.height(),
+            aspect_ratio
+        );
+    }
 } 
\ No newline at end of file